@@ -1,3 +1,6 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 use std::{
@@ -29,6 +32,32 @@ struct Cli {
     /// Wait a specified delay to estimate throughput
     #[arg(short = 'W', long)]
     wait_delay: Option<f64>,
+
+    /// Continuously rescan and redraw in place instead of printing once
+    #[arg(short, long)]
+    monitor: bool,
+
+    /// Rescan interval in seconds for --monitor
+    #[arg(short = 'i', long, default_value_t = 1.0)]
+    interval: f64,
+
+    /// Track progress by blocks actually allocated on disk instead of the
+    /// logical file size (use for sparse files, e.g. `dd` disk images).
+    /// Auto-detected for fds open for writing when not set.
+    #[arg(short, long)]
+    sparse: bool,
+
+    /// Output format: human text, a JSON array, or one NDJSON record per
+    /// process (per tick, under --monitor)
+    #[arg(short = 'f', long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    Ndjson,
 }
 
 fn get_proc_exe(proc: &Path) -> Option<String> {
@@ -44,6 +73,31 @@ fn get_proc_exe(proc: &Path) -> Option<String> {
     None
 }
 
+/// Escape a string for embedding in a JSON string literal. The fd/exe names
+/// we serialize come straight from `/proc` paths, so this only needs to
+/// handle quotes, backslashes and control characters, not full unicode.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string_or_null(value: &Option<String>) -> String {
+    match value {
+        Some(v) => format!("\"{}\"", json_escape(v)),
+        None => "null".into(),
+    }
+}
+
 fn format_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
 
@@ -58,16 +112,44 @@ fn format_size(size: u64) -> String {
     format!("{:.2}{}", rounded_size, UNITS[i])
 }
 
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+/// Block size used by `st_blocks`: always 512 bytes, regardless of the
+/// filesystem's own block size (`st_blksize`). See stat(2).
+const ST_BLOCKS_UNIT: u64 = 512;
+
+/// How many scans a fd's speed/ETA are averaged over. A single-tick delta
+/// is jittery; a handful of scans smooths it out without lagging too far
+/// behind a rate that's actually changing.
+const SAMPLE_WINDOW: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IOMode {
+    /// The fd offset advances as data moves, as with `read`/`write`.
+    Sequential,
+    /// The fd offset never moves (`pread`/`pwrite` with an explicit offset);
+    /// progress has to be inferred from the file growing instead.
+    Positional,
+}
+
 #[derive(Clone, Debug)]
 struct FD {
     _id: usize,
+    fd_path: PathBuf,
     fd_info: PathBuf,
     name: Option<String>,
     size: u64,
     pos: u64,
+    blocks: u64,
+    sparse: bool,
+    mode: Option<IOMode>,
     flags: FDFlags,
-    speed: Option<u64>,
-    last_scan: Instant,
+    /// Rolling window of (scan time, bytes transferred) used to smooth
+    /// speed and estimate an ETA instead of reacting to a single tick.
+    samples: VecDeque<(Instant, u64)>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -91,10 +173,18 @@ impl FDFlags {
             _ => Self::ReadOnly,
         }
     }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::ReadOnly => "read",
+            Self::WriteOnly => "write",
+            Self::ReadWrite => "read/write",
+        }
+    }
 }
 
 impl FD {
-    pub fn new(proc: &Path, id: usize) -> Option<Self> {
+    pub fn new(proc: &Path, id: usize, force_sparse: bool) -> Option<Self> {
         let fd = proc.join("fd").join(id.to_string());
         let fd_info = proc.join("fdinfo").join(id.to_string());
 
@@ -111,18 +201,43 @@ impl FD {
 
         let flags = FDFlags::from(flags_u64);
 
-        let fd_size = fs::metadata(fd).unwrap().len();
-
-        Some(FD {
+        let fd_meta = match fs::metadata(&fd) {
+            Ok(v) => v,
+            Err(_) => return None,
+        };
+        let fd_size = fd_meta.len();
+        let blocks = fd_meta.blocks();
+        // Auto-detection only helps the write side: a reader's `allocated()`
+        // never grows (it doesn't allocate anything), so switching a sparse
+        // *source* to block-accounting pins progress near 0% forever. Only
+        // writers and read/write fds get the block-based fallback; plain
+        // readers keep using `pos`, which already advances correctly.
+        let sparse = force_sparse
+            || (matches!(flags, FDFlags::WriteOnly | FDFlags::ReadWrite)
+                && Self::looks_sparse(blocks, fd_size));
+
+        let mut this = FD {
             _id: id,
             name: fd_link,
             pos,
             size: fd_size,
+            blocks,
+            sparse,
+            mode: None,
             flags,
-            speed: None,
-            last_scan: Instant::now(),
+            samples: VecDeque::with_capacity(SAMPLE_WINDOW),
+            fd_path: fd,
             fd_info,
-        })
+        };
+        this.push_sample();
+        Some(this)
+    }
+
+    /// A file is considered sparse when far fewer bytes are actually
+    /// allocated on disk than its logical length reports, e.g. a
+    /// preallocated disk image that `dd` is filling in gradually.
+    fn looks_sparse(blocks: u64, size: u64) -> bool {
+        size > 0 && (blocks * ST_BLOCKS_UNIT) < size / 2
     }
 
     pub fn update(&mut self) -> bool {
@@ -131,16 +246,57 @@ impl FD {
             Err(_) => return false,
         };
 
-        let elapsed = self.last_scan.elapsed();
-        let diff = pos - self.pos;
-        self.speed = Some((diff as f64 / elapsed.as_secs_f64()) as u64);
+        let old_pos = self.pos;
+        let old_size = self.size;
+
+        if let Ok(fd_meta) = fs::metadata(&self.fd_path) {
+            self.size = fd_meta.len();
+            self.blocks = fd_meta.blocks();
+        }
+
+        if self.mode.is_none() {
+            let stuck_at_offset = pos == old_pos && self.size > old_size;
+            self.mode = Some(if stuck_at_offset {
+                IOMode::Positional
+            } else {
+                IOMode::Sequential
+            });
+            // Earlier samples recorded the fd offset; a positional fd now
+            // measures the file length, so restate them in those terms.
+            if stuck_at_offset {
+                for sample in self.samples.iter_mut() {
+                    sample.1 = old_size;
+                }
+            }
+        }
 
         self.pos = pos;
-        self.last_scan = Instant::now();
+        self.push_sample();
 
         true
     }
 
+    fn push_sample(&mut self) {
+        self.samples.push_back((Instant::now(), self.transferred()));
+        if self.samples.len() > SAMPLE_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes/sec averaged over the whole sample window, rather than just
+    /// the last two scans.
+    fn smoothed_rate(&self) -> Option<f64> {
+        let (first_t, first_v) = *self.samples.front()?;
+        let (last_t, last_v) = *self.samples.back()?;
+
+        let elapsed = last_t.duration_since(first_t).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+
+        Some(last_v.saturating_sub(first_v) as f64 / elapsed)
+    }
+
     fn read_fdinfo(path: PathBuf) -> io::Result<(u64, u64)> {
         let infos = fs::read_to_string(path)?;
 
@@ -162,16 +318,80 @@ impl FD {
                 flags_value.parse::<u64>().unwrap(),
             ))
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, "something is broken"))
+            Err(io::Error::other("something is broken"))
+        }
+    }
+
+    /// Bytes actually allocated on disk, per `st_blocks`.
+    pub fn allocated(&self) -> u64 {
+        self.blocks * ST_BLOCKS_UNIT
+    }
+
+    /// The byte offset to report as "transferred so far": the raw fd
+    /// position for normal files, allocated bytes for sparse ones where the
+    /// position is meaningless against the logical size, or the file's own
+    /// length for positional fds whose offset never moves.
+    pub fn transferred(&self) -> u64 {
+        if self.mode == Some(IOMode::Positional) {
+            self.size
+        } else if self.sparse {
+            self.allocated()
+        } else {
+            self.pos
         }
     }
 
+    /// Whether `progress`/`size` are meaningful. Positional fds only ever
+    /// tell us how much has landed so far, not a total to divide by.
+    pub fn has_known_total(&self) -> bool {
+        self.mode != Some(IOMode::Positional) && self.size > 0
+    }
+
     pub fn progress(&self) -> f32 {
-        (self.pos as f32) / self.size as f32
+        (self.transferred() as f32) / self.size as f32
     }
 
     pub fn speed(&self) -> Option<u64> {
-        self.speed
+        self.smoothed_rate().map(|r| r as u64)
+    }
+
+    /// Time remaining at the current smoothed rate, or `None` when there's
+    /// no known total to count down to or the rate is zero/unknown.
+    pub fn eta(&self) -> Option<Duration> {
+        if !self.has_known_total() {
+            return None;
+        }
+        let rate = self.smoothed_rate()?;
+        if rate <= 0.0 {
+            return None;
+        }
+        let remaining = self.size.saturating_sub(self.transferred()) as f64;
+        Some(Duration::from_secs_f64(remaining / rate))
+    }
+
+    /// Serialize as a raw-numbers JSON object: bytes, not human-formatted
+    /// sizes, so downstream consumers (`jq`, dashboards) can format it
+    /// themselves. `pos` is always the raw fd offset so its meaning is
+    /// stable across fds; `transferred` is the (possibly sparse/positional)
+    /// estimate used for `progress`/`speed`/`eta_secs`.
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"name":{},"flags":"{}","pos":{},"transferred":{},"size":{},"progress":{},"speed":{},"eta_secs":{}}}"#,
+            json_string_or_null(&self.name),
+            self.flags.label(),
+            self.pos,
+            self.transferred(),
+            self.size,
+            if self.has_known_total() {
+                self.progress().to_string()
+            } else {
+                "null".into()
+            },
+            self.speed().map(|s| s.to_string()).unwrap_or_else(|| "null".into()),
+            self.eta()
+                .map(|eta| eta.as_secs().to_string())
+                .unwrap_or_else(|| "null".into()),
+        )
     }
 }
 
@@ -184,7 +404,7 @@ struct Proc {
 }
 
 impl Proc {
-    fn new(exe: String, path: PathBuf) -> Self {
+    fn new(exe: String, path: PathBuf, sparse: bool) -> Self {
         let pid = path
             .file_name()
             .unwrap()
@@ -198,86 +418,147 @@ impl Proc {
             fd: vec![],
             pid,
         };
-        p.get_file_descriptors();
+        p.get_file_descriptors(sparse);
         p
     }
 
-    fn get_file_descriptors(&mut self) {
+    fn get_file_descriptors(&mut self, sparse: bool) {
         let fd_dir = self.path.join("fd");
         let fd = fs::read_dir(fd_dir)
             .unwrap()
             .filter_map(|x| x.ok())
             .filter_map(|x| x.file_name().into_string().unwrap().parse::<usize>().ok())
-            .filter_map(|id| FD::new(&self.path, id))
+            .filter_map(|id| FD::new(&self.path, id, sparse))
             .collect::<Vec<_>>();
         self.fd = fd;
     }
 
-    fn find_biggest_fd(&self, flag_type: FDFlags) -> Option<&FD> {
-        self.fd
+    /// Fds worth reporting on: regular files at or above `MIN_INTERESTING_FD_SIZE`,
+    /// biggest first. Small fds (config files, sockets, `/dev/null`, ...) are noise.
+    fn interesting_fds(&self) -> Vec<&FD> {
+        let mut fds: Vec<&FD> = self
+            .fd
             .iter()
-            .filter(|x| x.flags == flag_type)
-            .max_by_key(|x| x.size)
+            .filter(|fd| fd.size >= MIN_INTERESTING_FD_SIZE)
+            .collect();
+        fds.sort_by_key(|fd| std::cmp::Reverse(fd.size));
+        fds
     }
 
     fn update(&mut self) {
         self.fd.retain_mut(|x| x.update());
     }
 
-    fn print(&self) {
-        let fd_read = self.find_biggest_fd(FDFlags::ReadOnly);
-        let fd_write = self.find_biggest_fd(FDFlags::WriteOnly);
-        println!(
-            "[{}] {} {} > {}",
-            self.pid,
-            self.exe,
-            fd_read.unwrap().name.as_ref().unwrap(),
-            match fd_write {
-                Some(fd) => fd.name.as_ref().unwrap(),
-                None => "",
+    /// Whether this process still has any live, tracked file descriptor.
+    ///
+    /// Once every fd a process held has vanished (closed or the process
+    /// exited), there is nothing left to report for it.
+    fn is_alive(&self) -> bool {
+        !self.fd.is_empty()
+    }
+
+    fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => self.print_text(),
+            OutputFormat::Json | OutputFormat::Ndjson => println!("{}", self.to_json()),
+        }
+    }
+
+    /// Aggregate throughput across every reported fd, summed per direction
+    /// and then maxed rather than summed outright: for a `dd if=... of=...`
+    /// (or any read-into-write pipe), the read side and the write side are
+    /// the same bytes counted twice, so adding them would report roughly
+    /// double the real disk throughput.
+    fn total_speed(fds: &[&FD]) -> u64 {
+        let (read, write) = fds.iter().fold((0u64, 0u64), |(read, write), fd| {
+            let speed = fd.speed().unwrap_or(0);
+            match fd.flags {
+                FDFlags::WriteOnly => (read, write + speed),
+                FDFlags::ReadOnly | FDFlags::ReadWrite => (read + speed, write),
             }
-        );
+        });
+        read.max(write)
+    }
 
-        let speed = match fd_read.unwrap().speed() {
-            Some(s) => format!("{}/s", format_size(s)),
-            None => String::new(),
-        };
+    fn to_json(&self) -> String {
+        let fds = self.interesting_fds();
+        let fds_json = fds
+            .iter()
+            .map(|fd| fd.to_json())
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            r#"{{"pid":{},"exe":"{}","fds":[{}],"speed":{}}}"#,
+            self.pid,
+            json_escape(&self.exe),
+            fds_json,
+            Self::total_speed(&fds),
+        )
+    }
 
-        if let Some(fd) = fd_read {
-            if fd.size > 0 {
+    fn print_text(&self) {
+        let fds = self.interesting_fds();
+        if fds.is_empty() {
+            // Matched by name, but nothing large enough to report on (yet).
+            return;
+        }
+
+        println!("[{}] {}", self.pid, self.exe);
+
+        for fd in &fds {
+            let name = fd.name.as_deref().unwrap_or("?");
+            let speed = match fd.speed() {
+                Some(s) => format!("{}/s", format_size(s)),
+                None => String::new(),
+            };
+
+            if fd.has_known_total() {
+                let eta = match fd.eta() {
+                    Some(eta) => format!(" eta {}", format_eta(eta)),
+                    None => String::new(),
+                };
                 println!(
-                    "\t{:.2}% ({} / {}) {}",
-                    fd_read.unwrap().progress() * 100.,
-                    format_size(fd.pos),
+                    "\t{} {} {:.2}% ({} / {}) {}{}",
+                    fd.flags.label(),
+                    name,
+                    fd.progress() * 100.,
+                    format_size(fd.transferred()),
                     format_size(fd.size),
+                    speed,
+                    eta
+                );
+            } else if fd.transferred() > 0 {
+                println!(
+                    "\t{} {} {} transferred {}",
+                    fd.flags.label(),
+                    name,
+                    format_size(fd.transferred()),
                     speed
                 );
             } else {
-                println!("\tUnknown progress")
+                println!("\t{} {} unknown progress", fd.flags.label(), name);
             }
         }
 
+        if fds.len() > 1 {
+            println!("\ttotal {}/s", format_size(Self::total_speed(&fds)));
+        }
+
         println!();
     }
 }
 
 const PROGS: &[&str] = &["cp", "mv", "dd", "cat"];
 
-fn main() -> io::Result<()> {
-    let cli = Cli::parse();
-
-    let mut progs_to_watch: Vec<&str> = if let Some(prog_list) = &cli.command {
-        Vec::from_iter(prog_list.iter().map(|x| x as &str))
-    } else {
-        PROGS.into()
-    };
-
-    if let Some(additional_commands) = &cli.additional_command {
-        progs_to_watch.extend(additional_commands.iter().map(|x| x as &str));
-    }
+/// Fds smaller than this are treated as noise (config files, sockets,
+/// `/dev/null`, ...) and left out of the report entirely.
+const MIN_INTERESTING_FD_SIZE: u64 = 1024 * 1024;
 
-    let procs: Vec<usize> = if let Some(pids) = cli.pid {
-        pids
+/// Scan `/proc` (or a fixed `pids` list) for processes matching `progs_to_watch`
+/// and build a `Proc` for each, already populated with its current fds.
+fn discover_procs(progs_to_watch: &[&str], pids: Option<&[usize]>, sparse: bool) -> Vec<Proc> {
+    let procs: Vec<usize> = if let Some(pids) = pids {
+        pids.to_vec()
     } else {
         fs::read_dir("/proc")
             .expect("procfs is not accessible")
@@ -286,20 +567,101 @@ fn main() -> io::Result<()> {
             .collect::<Vec<_>>()
     };
 
-    let mut filtered_procs = procs
+    procs
         .iter()
         .map(|pid| PathBuf::from("/proc").join(format!("{}", pid)))
         .map(|x| (x.clone(), get_proc_exe(&x)))
         .filter(|x| x.1.is_some())
         .filter(|x| progs_to_watch.iter().any(|p| *p == x.1.as_ref().unwrap()))
-        .map(|x| Proc::new(x.1.unwrap(), x.0))
-        .collect::<Vec<_>>();
+        .map(|x| Proc::new(x.1.unwrap(), x.0, sparse))
+        .collect::<Vec<_>>()
+}
+
+/// Clear the terminal and move the cursor back to the top-left so the next
+/// frame overwrites the previous one instead of scrolling.
+fn clear_screen() {
+    print!("\x1B[2J\x1B[1;1H");
+    let _ = io::stdout().flush();
+}
+
+/// Render a full snapshot of tracked processes: one block of lines per
+/// process for `text`, one line per process for `ndjson`, or a single JSON
+/// array document for `json`.
+fn render(procs: &[Proc], format: OutputFormat) {
+    match format {
+        OutputFormat::Text | OutputFormat::Ndjson => {
+            for p in procs {
+                p.print(format);
+            }
+        }
+        OutputFormat::Json => {
+            let body = procs
+                .iter()
+                .map(Proc::to_json)
+                .collect::<Vec<_>>()
+                .join(",");
+            println!("[{}]", body);
+        }
+    }
+}
+
+fn monitor(
+    progs_to_watch: &[&str],
+    pids: Option<&[usize]>,
+    interval: Duration,
+    sparse: bool,
+    format: OutputFormat,
+) -> io::Result<()> {
+    let mut tracked = discover_procs(progs_to_watch, pids, sparse);
+
+    loop {
+        sleep(interval);
+
+        for p in &mut tracked {
+            p.update();
+        }
+        tracked.retain(Proc::is_alive);
+
+        let known: Vec<usize> = tracked.iter().map(|p| p.pid).collect();
+        let discovered = discover_procs(progs_to_watch, pids, sparse)
+            .into_iter()
+            .filter(|p| !known.contains(&p.pid));
+        tracked.extend(discovered);
+
+        if format == OutputFormat::Text {
+            clear_screen();
+        }
+        render(&tracked, format);
+    }
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+
+    let mut progs_to_watch: Vec<&str> = if let Some(prog_list) = &cli.command {
+        Vec::from_iter(prog_list.iter().map(|x| x as &str))
+    } else {
+        PROGS.into()
+    };
+
+    if let Some(additional_commands) = &cli.additional_command {
+        progs_to_watch.extend(additional_commands.iter().map(|x| x as &str));
+    }
+
+    if cli.monitor {
+        return monitor(
+            &progs_to_watch,
+            cli.pid.as_deref(),
+            Duration::from_secs_f64(cli.interval),
+            cli.sparse,
+            cli.format,
+        );
+    }
+
+    let mut filtered_procs = discover_procs(&progs_to_watch, cli.pid.as_deref(), cli.sparse);
 
     if cli.wait || cli.wait_delay.is_some() {
-        let duration = match cli.wait_delay {
-            Some(v) => v,
-            None => 1.0,
-        };
+        let duration = cli.wait_delay.unwrap_or(1.0);
 
         sleep(Duration::from_secs_f64(duration));
 
@@ -308,9 +670,7 @@ fn main() -> io::Result<()> {
         }
     }
 
-    for p in filtered_procs {
-        p.print();
-    }
+    render(&filtered_procs, cli.format);
 
     Ok(())
 }